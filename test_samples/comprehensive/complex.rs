@@ -1,23 +1,178 @@
 // Comprehensive Rust test file with complex structures
 use std::collections::HashMap;
 use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+use argon2::{
+    password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Algorithm, Argon2, Params, Version,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use rand_core::OsRng;
+use ring::rand::{SecureRandom, SystemRandom};
 
 /// Authenticator trait
 pub trait Authenticator {
-    fn authenticate(&self, username: &str, password: &str) -> bool;
+    fn authenticate(&mut self, username: &str, password: &str) -> AuthOutcome;
     fn logout(&self);
 }
 
+/// Fine-grained result of an authentication attempt, distinguishing a
+/// disabled or locked account from a plain bad password so callers can map
+/// each case to the right `StatusCode`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthOutcome {
+    Success,
+    BadPassword,
+    AccountDisabled,
+    AccountLocked,
+}
+
+/// Bit set on `User::flags` marking the account as disabled.
+pub const FLAG_DISABLED: i32 = 1 << 0;
+
+/// Configurable failed-login lockout policy.
+#[derive(Debug, Clone, Copy)]
+pub struct LockoutConfig {
+    pub max_failures: i64,
+}
+
+impl Default for LockoutConfig {
+    fn default() -> Self {
+        LockoutConfig { max_failures: 5 }
+    }
+}
+
+/// Tunable Argon2id cost parameters for password hashing.
+///
+/// `Default` picks production-grade costs; tests that need to hash a lot of
+/// passwords quickly should use [`PasswordConfig::cheap`] instead.
+#[derive(Debug, Clone, Copy)]
+pub struct PasswordConfig {
+    pub memory_cost_kib: u32,
+    pub time_cost: u32,
+    pub parallelism: u32,
+}
+
+impl Default for PasswordConfig {
+    fn default() -> Self {
+        PasswordConfig {
+            memory_cost_kib: 19 * 1024,
+            time_cost: 2,
+            parallelism: 1,
+        }
+    }
+}
+
+impl PasswordConfig {
+    /// A deliberately cheap parameter set for tests and fixtures.
+    pub fn cheap() -> Self {
+        PasswordConfig {
+            memory_cost_kib: 8,
+            time_cost: 1,
+            parallelism: 1,
+        }
+    }
+
+    fn build(&self) -> Argon2<'static> {
+        let params = Params::new(self.memory_cost_kib, self.time_cost, self.parallelism, None)
+            .expect("valid argon2 params");
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+    }
+}
+
 /// Base User struct
 #[derive(Debug, Clone)]
 pub struct User {
     pub username: String,
     pub id: u32,
+    pub password_hash: Option<String>,
+    pub flags: i32,
+    pub password_failure_count: i64,
+    lockout_threshold: i64,
 }
 
 impl User {
     pub fn new(username: String, id: u32) -> Self {
-        User { username, id }
+        User {
+            username,
+            id,
+            password_hash: None,
+            flags: 0,
+            password_failure_count: 0,
+            lockout_threshold: LockoutConfig::default().max_failures,
+        }
+    }
+
+    pub fn is_disabled(&self) -> bool {
+        self.flags & FLAG_DISABLED != 0
+    }
+
+    pub fn disable(&mut self) {
+        self.flags |= FLAG_DISABLED;
+    }
+
+    pub fn enable(&mut self) {
+        self.flags &= !FLAG_DISABLED;
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.password_failure_count >= self.lockout_threshold
+    }
+
+    pub fn set_lockout_threshold(&mut self, lockout: &LockoutConfig) {
+        self.lockout_threshold = lockout.max_failures;
+    }
+
+    /// Stores an already-computed PHC hash string directly, e.g. one read
+    /// from a `shadow`-style record, bypassing `set_password`'s hashing.
+    pub fn set_password_hash(&mut self, hash: String) {
+        self.password_hash = Some(hash);
+    }
+
+    /// Checks `password` against the stored hash, short-circuiting for a
+    /// disabled or already-locked account. Increments the failure counter
+    /// on a wrong password and resets it on success.
+    fn check_password(&mut self, password: &str) -> AuthOutcome {
+        if self.is_disabled() {
+            return AuthOutcome::AccountDisabled;
+        }
+        if self.is_locked() {
+            return AuthOutcome::AccountLocked;
+        }
+        if self.verify_password(password) {
+            self.password_failure_count = 0;
+            AuthOutcome::Success
+        } else {
+            self.password_failure_count += 1;
+            AuthOutcome::BadPassword
+        }
+    }
+
+    /// Hashes `password` with Argon2id under `config` and stores the
+    /// resulting PHC string (`$argon2id$v=19$m=...,t=...,p=...$salt$hash`).
+    pub fn set_password(&mut self, password: &str, config: &PasswordConfig) {
+        let salt = SaltString::generate(&mut OsRng);
+        let hash = config
+            .build()
+            .hash_password(password.as_bytes(), &salt)
+            .expect("argon2 hashing should not fail for valid input")
+            .to_string();
+        self.password_hash = Some(hash);
+    }
+
+    /// Re-derives the password under the parameters embedded in the stored
+    /// PHC string and compares in constant time.
+    fn verify_password(&self, password: &str) -> bool {
+        let Some(stored) = &self.password_hash else {
+            return false;
+        };
+        let Ok(parsed) = PasswordHash::new(stored) else {
+            return false;
+        };
+        Argon2::default()
+            .verify_password(password.as_bytes(), &parsed)
+            .is_ok()
     }
 }
 
@@ -35,49 +190,279 @@ impl AdminUser {
             permissions: Vec::new(),
         }
     }
-    
+
     pub fn add_permission(&mut self, permission: String) {
         self.permissions.push(permission);
     }
-    
-    fn validate_password(&self, password: &str) -> bool {
-        password.len() >= 8
+
+    /// Hashes and stores `password` for this admin using Argon2id.
+    pub fn set_password(&mut self, password: &str, config: &PasswordConfig) {
+        self.user.set_password(password, config);
+    }
+
+    /// Stores an already-computed PHC hash string directly, e.g. one read
+    /// from a `shadow`-style record.
+    pub fn set_password_hash(&mut self, hash: String) {
+        self.user.set_password_hash(hash);
+    }
+
+    fn validate_password(&mut self, password: &str) -> AuthOutcome {
+        self.user.check_password(password)
+    }
+
+    pub fn is_disabled(&self) -> bool {
+        self.user.is_disabled()
+    }
+
+    pub fn disable(&mut self) {
+        self.user.disable();
+    }
+
+    pub fn enable(&mut self) {
+        self.user.enable();
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.user.is_locked()
+    }
+
+    pub fn set_lockout_threshold(&mut self, lockout: &LockoutConfig) {
+        self.user.set_lockout_threshold(lockout);
+    }
+
+    /// Returns true if any granted permission covers `needed`, honoring
+    /// trailing-wildcard tokens like `lab.test.*`.
+    pub fn has_permission(&self, needed: &str) -> bool {
+        self.permissions
+            .iter()
+            .any(|granted| Permission::new(granted.clone()).grants(needed))
+    }
+}
+
+impl Authorizer for AdminUser {
+    fn has_permission(&self, needed: &str) -> bool {
+        AdminUser::has_permission(self, needed)
     }
 }
 
 impl Authenticator for AdminUser {
-    fn authenticate(&self, username: &str, password: &str) -> bool {
-        if username.is_empty() || password.is_empty() {
-            return false;
+    fn authenticate(&mut self, username: &str, password: &str) -> AuthOutcome {
+        if username.is_empty() || password.is_empty() || self.user.username != username {
+            return AuthOutcome::BadPassword;
         }
-        self.user.username == username && self.validate_password(password)
+        self.validate_password(password)
     }
-    
+
     fn logout(&self) {
         println!("Admin logged out");
     }
 }
 
-/// Generic SessionManager
+/// A dotted permission token such as `lab.test.read`. A token ending in
+/// `.*` is a trailing wildcard grant that covers any token sharing its
+/// prefix; any other token only grants itself.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Permission(String);
+
+impl Permission {
+    pub fn new(token: impl Into<String>) -> Self {
+        Permission(token.into())
+    }
+
+    /// Returns true if this granted token covers the `needed` request.
+    pub fn grants(&self, needed: &str) -> bool {
+        match self.0.strip_suffix(".*") {
+            Some(prefix) => needed == prefix || needed.starts_with(&format!("{prefix}.")),
+            None => self.0 == needed,
+        }
+    }
+}
+
+/// A named role granting a set of permissions, optionally inheriting from
+/// one or more parent roles.
+#[derive(Debug, Clone)]
+pub struct Role {
+    pub name: String,
+    pub permissions: Vec<String>,
+    pub parents: Vec<String>,
+}
+
+/// Errors raised while resolving a role's effective permission set.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RoleError {
+    UnknownRole(String),
+    Cycle(String),
+}
+
+/// Computes the effective permission set for `role_name` by walking the
+/// role graph depth-first and unioning each role's permissions with its
+/// parents'. Rejects cycles by tracking visited role names on the stack.
+pub fn resolve_permissions(
+    role_name: &str,
+    roles: &HashMap<String, Role>,
+) -> Result<Vec<String>, RoleError> {
+    let mut stack = Vec::new();
+    let mut effective = std::collections::HashSet::new();
+    resolve_into(role_name, roles, &mut stack, &mut effective)?;
+    Ok(effective.into_iter().collect())
+}
+
+fn resolve_into(
+    role_name: &str,
+    roles: &HashMap<String, Role>,
+    stack: &mut Vec<String>,
+    effective: &mut std::collections::HashSet<String>,
+) -> Result<(), RoleError> {
+    if stack.iter().any(|name| name == role_name) {
+        return Err(RoleError::Cycle(role_name.to_string()));
+    }
+    let role = roles
+        .get(role_name)
+        .ok_or_else(|| RoleError::UnknownRole(role_name.to_string()))?;
+
+    stack.push(role_name.to_string());
+    effective.extend(role.permissions.iter().cloned());
+    for parent in &role.parents {
+        resolve_into(parent, roles, stack, effective)?;
+    }
+    stack.pop();
+    Ok(())
+}
+
+/// Extension of `Authenticator` for types that can additionally be checked
+/// against a required permission once authenticated.
+pub trait Authorizer: Authenticator {
+    fn has_permission(&self, needed: &str) -> bool;
+}
+
+/// The method-dependent authentication credential: the identity an
+/// `Authenticator` validates (e.g. a username). This is never used to key
+/// a session or a permission check directly — see `AuthZId`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct AuthCId(pub String);
+
+/// The internal authorization identity a session is created for; it is
+/// stored with the session entry, which is looked up by `SessionToken`.
+/// Distinct from `AuthCId` so a single authenticated credential can act as
+/// more than one authorization identity, e.g. a base account versus an
+/// elevated `+admin` sub-account. Permission checks are made against the
+/// user's own grants, not the `AuthZId`, so the base and `+admin`
+/// identities of one user pass and fail the same permission checks.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct AuthZId {
+    pub uid: String,
+    pub subuid: String,
+    pub realm: String,
+}
+
+impl AuthZId {
+    /// The base authorization identity for `uid` within `realm` (no subuid).
+    pub fn base(uid: impl Into<String>, realm: impl Into<String>) -> Self {
+        AuthZId {
+            uid: uid.into(),
+            subuid: String::new(),
+            realm: realm.into(),
+        }
+    }
+
+    /// A sub-identity of `uid` within `realm`, e.g. subuid `"admin"`.
+    pub fn with_subuid(
+        uid: impl Into<String>,
+        subuid: impl Into<String>,
+        realm: impl Into<String>,
+    ) -> Self {
+        AuthZId {
+            uid: uid.into(),
+            subuid: subuid.into(),
+            realm: realm.into(),
+        }
+    }
+}
+
+/// A cryptographically random session token, rendered as URL-safe base64,
+/// used as the external handle clients present to look up a session —
+/// unlike an `AuthZId` or sequential id, it carries no guessable structure.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SessionToken(String);
+
+impl SessionToken {
+    /// Generates a fresh token backed by ~32 bytes of CSPRNG entropy.
+    fn generate() -> Self {
+        let rng = SystemRandom::new();
+        let mut bytes = [0u8; 32];
+        rng.fill(&mut bytes).expect("system RNG should not fail");
+        SessionToken(URL_SAFE_NO_PAD.encode(bytes))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+/// A stored session: the user, the authorization identity it was created
+/// for, and when it was created/expires.
+struct SessionEntry<T> {
+    user: T,
+    authzid: AuthZId,
+    created_at: Instant,
+    expires_at: Instant,
+}
+
+/// Generic SessionManager. Sessions are looked up by `SessionToken` and
+/// expire after a configurable TTL; expired entries are treated as absent
+/// and evicted lazily, with `sweep_expired` available for bulk cleanup.
 pub struct SessionManager<T> {
-    sessions: Arc<RwLock<HashMap<String, T>>>,
+    sessions: Arc<RwLock<HashMap<SessionToken, SessionEntry<T>>>>,
+    ttl: Duration,
 }
 
 impl<T: Clone> SessionManager<T> {
-    pub fn new() -> Self {
+    pub fn new(ttl: Duration) -> Self {
         SessionManager {
             sessions: Arc::new(RwLock::new(HashMap::new())),
+            ttl,
         }
     }
-    
-    pub fn create_session(&self, session_id: String, user: T) {
+
+    /// Creates a session for `authzid`/`user` and returns the
+    /// CSPRNG-generated token that identifies it.
+    pub fn create_session(&self, authzid: AuthZId, user: T) -> SessionToken {
+        let token = SessionToken::generate();
+        let now = Instant::now();
+        let entry = SessionEntry {
+            user,
+            authzid,
+            created_at: now,
+            expires_at: now + self.ttl,
+        };
         let mut sessions = self.sessions.write().unwrap();
-        sessions.insert(session_id, user);
+        sessions.insert(token.clone(), entry);
+        token
+    }
+
+    /// Returns the session's user, treating an expired entry as absent and
+    /// lazily evicting it.
+    pub fn get_session(&self, token: &SessionToken) -> Option<T> {
+        let now = Instant::now();
+        {
+            let sessions = self.sessions.read().unwrap();
+            match sessions.get(token) {
+                Some(entry) if entry.expires_at > now => return Some(entry.user.clone()),
+                None => return None,
+                Some(_) => {}
+            }
+        }
+        self.sessions.write().unwrap().remove(token);
+        None
     }
-    
-    pub fn get_session(&self, session_id: &str) -> Option<T> {
-        let sessions = self.sessions.read().unwrap();
-        sessions.get(session_id).cloned()
+
+    /// Removes every expired session in one pass, for periodic bulk cleanup.
+    pub fn sweep_expired(&self) {
+        let now = Instant::now();
+        self.sessions
+            .write()
+            .unwrap()
+            .retain(|_, entry| entry.expires_at > now);
     }
 }
 
@@ -104,18 +489,238 @@ impl StatusCode {
 /// Result type alias
 pub type AuthResult<T> = Result<T, StatusCode>;
 
+/// The authorization identities a successfully authenticated `AuthCId` is
+/// permitted to act as within `realm`: always the base account, plus an
+/// elevated `+admin` subuid when the user carries the `admin.*` permission.
+fn permitted_authzids<T: Authorizer>(user: &T, authcid: &AuthCId, realm: &str) -> Vec<AuthZId> {
+    let mut ids = vec![AuthZId::base(authcid.0.clone(), realm)];
+    if user.has_permission("admin.*") {
+        ids.push(AuthZId::with_subuid(authcid.0.clone(), "admin", realm));
+    }
+    ids
+}
+
 /// Complex function with error handling
-pub fn authenticate_and_create_session<T: Authenticator + Clone>(
-    user: &T,
-    username: &str,
+///
+/// Authenticates `authcid` and maps it to the authorization identity it
+/// will act as for the rest of the session. Pass `requested_subuid` to pick
+/// an elevated identity (e.g. `Some("admin")`); a request for a subuid the
+/// account isn't permitted to use is rejected with `StatusCode::Forbidden`.
+pub fn authenticate_and_create_session<T: Authorizer + Clone>(
+    user: &mut T,
+    authcid: &AuthCId,
     password: &str,
+    realm: &str,
+    requested_subuid: Option<&str>,
     manager: &SessionManager<T>,
-) -> AuthResult<String> {
-    if user.authenticate(username, password) {
-        let session_id = format!("session_{}", uuid::Uuid::new_v4());
-        manager.create_session(session_id.clone(), user.clone());
-        Ok(session_id)
-    } else {
-        Err(StatusCode::Unauthorized)
+) -> AuthResult<SessionToken> {
+    match user.authenticate(&authcid.0, password) {
+        AuthOutcome::Success => {}
+        AuthOutcome::BadPassword => return Err(StatusCode::Unauthorized),
+        AuthOutcome::AccountDisabled | AuthOutcome::AccountLocked => {
+            return Err(StatusCode::Forbidden)
+        }
+    }
+    let permitted = permitted_authzids(user, authcid, realm);
+    let authzid = match requested_subuid {
+        Some(subuid) => permitted
+            .into_iter()
+            .find(|id| id.subuid == subuid)
+            .ok_or(StatusCode::Forbidden)?,
+        None => permitted
+            .into_iter()
+            .next()
+            .expect("an authenticated user always has a base authzid"),
+    };
+    Ok(manager.create_session(authzid, user.clone()))
+}
+
+/// Like `authenticate_and_create_session`, but additionally requires the
+/// resulting authorization identity's user to hold `required_permission`.
+/// An authenticated user lacking the permission is rejected with
+/// `StatusCode::Forbidden`, distinct from the `StatusCode::Unauthorized`
+/// returned for bad credentials.
+pub fn authorize_and_create_session<T: Authorizer + Clone>(
+    user: &mut T,
+    authcid: &AuthCId,
+    password: &str,
+    realm: &str,
+    requested_subuid: Option<&str>,
+    required_permission: &str,
+    manager: &SessionManager<T>,
+) -> AuthResult<SessionToken> {
+    let token =
+        authenticate_and_create_session(user, authcid, password, realm, requested_subuid, manager)?;
+    if !user.has_permission(required_permission) {
+        manager.sessions.write().unwrap().remove(&token);
+        return Err(StatusCode::Forbidden);
+    }
+    Ok(token)
+}
+
+/// A single malformed line skipped while loading a `UserStore`, recording
+/// enough context to diagnose it without aborting the whole load.
+#[derive(Debug, Clone)]
+pub struct LoadError {
+    pub line_number: usize,
+    pub line: String,
+    pub reason: String,
+}
+
+struct PasswdRecord {
+    username: String,
+    uid: u32,
+}
+
+struct ShadowRecord {
+    username: String,
+    password_hash: String,
+}
+
+struct GroupRecord {
+    name: String,
+    members: Vec<String>,
+}
+
+fn parse_passwd(contents: &str, errors: &mut Vec<LoadError>) -> Vec<PasswdRecord> {
+    let mut records = Vec::new();
+    for (idx, line) in contents.lines().enumerate() {
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+        let mut fields = trimmed.split(':');
+        let username = fields.next().unwrap_or_default();
+        let uid = fields.next().and_then(|f| f.parse::<u32>().ok());
+        match uid {
+            Some(uid) if !username.is_empty() => records.push(PasswdRecord {
+                username: username.to_string(),
+                uid,
+            }),
+            _ => errors.push(LoadError {
+                line_number: idx + 1,
+                line: line.to_string(),
+                reason: "malformed passwd record".to_string(),
+            }),
+        }
+    }
+    records
+}
+
+fn parse_shadow(contents: &str, errors: &mut Vec<LoadError>) -> Vec<ShadowRecord> {
+    let mut records = Vec::new();
+    for (idx, line) in contents.lines().enumerate() {
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+        let mut fields = trimmed.splitn(3, ':');
+        let username = fields.next().unwrap_or_default();
+        let password_hash = fields.next();
+        match password_hash {
+            Some(hash) if !username.is_empty() && !hash.is_empty() => {
+                records.push(ShadowRecord {
+                    username: username.to_string(),
+                    password_hash: hash.to_string(),
+                })
+            }
+            _ => errors.push(LoadError {
+                line_number: idx + 1,
+                line: line.to_string(),
+                reason: "malformed shadow record".to_string(),
+            }),
+        }
+    }
+    records
+}
+
+fn parse_group(contents: &str, errors: &mut Vec<LoadError>) -> Vec<GroupRecord> {
+    let mut records = Vec::new();
+    for (idx, line) in contents.lines().enumerate() {
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+        let mut fields = trimmed.split(':');
+        let name = fields.next().unwrap_or_default();
+        if name.is_empty() {
+            errors.push(LoadError {
+                line_number: idx + 1,
+                line: line.to_string(),
+                reason: "malformed group record".to_string(),
+            });
+            continue;
+        }
+        let members = fields
+            .last()
+            .map(|members| {
+                members
+                    .split(',')
+                    .filter(|member| !member.is_empty())
+                    .map(str::to_string)
+                    .collect()
+            })
+            .unwrap_or_default();
+        records.push(GroupRecord {
+            name: name.to_string(),
+            members,
+        });
+    }
+    records
+}
+
+/// Loads `User`/`AdminUser` records from classic colon-delimited
+/// `passwd`/`shadow`/`group` files, so the crate can authenticate against
+/// an on-disk user database instead of only in-memory `AdminUser` instances.
+pub struct UserStore {
+    users: HashMap<String, AdminUser>,
+}
+
+impl UserStore {
+    /// Parses `passwd`, `shadow`, and `group` file contents into a
+    /// `UserStore`, cross-linking shadow hashes to passwd entries by
+    /// username and seeding role assignments (as `<group>.*` permissions)
+    /// from group membership. A passwd record is read as `username:uid:...`,
+    /// with the uid in the second field. Comment lines are skipped; malformed
+    /// rows are recorded as recoverable `LoadError`s rather than aborting the
+    /// load.
+    pub fn load(passwd: &str, shadow: &str, group: &str) -> (UserStore, Vec<LoadError>) {
+        let mut errors = Vec::new();
+        let passwd_records = parse_passwd(passwd, &mut errors);
+        let shadow_records = parse_shadow(shadow, &mut errors);
+        let group_records = parse_group(group, &mut errors);
+
+        let mut shadow_by_username: HashMap<String, String> = shadow_records
+            .into_iter()
+            .map(|record| (record.username, record.password_hash))
+            .collect();
+
+        let mut users = HashMap::new();
+        for record in passwd_records {
+            let mut admin = AdminUser::new(record.username.clone(), record.uid);
+            if let Some(hash) = shadow_by_username.remove(&record.username) {
+                admin.set_password_hash(hash);
+            }
+            users.insert(record.username, admin);
+        }
+
+        for group in &group_records {
+            for member in &group.members {
+                if let Some(user) = users.get_mut(member) {
+                    user.add_permission(format!("{}.*", group.name));
+                }
+            }
+        }
+
+        (UserStore { users }, errors)
+    }
+
+    /// Resolves `username`, pulls its shadow-sourced hash, and delegates to
+    /// the existing `Authenticator` verification path.
+    pub fn authenticate(&mut self, username: &str, password: &str) -> AuthOutcome {
+        let Some(user) = self.users.get_mut(username) else {
+            return AuthOutcome::BadPassword;
+        };
+        user.authenticate(username, password)
     }
 }